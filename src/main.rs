@@ -1,54 +1,104 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs::read_to_string;
-use std::io::{Bytes, Read, Stdin, Write, stdin, stdout};
+use std::io::{Bytes, Read, Write, stdin, stdout};
 use std::path::Path;
 
 fn main() {
     let args = get_args();
 
-    if args.len() != 1 {
+    let mut emit_c = false;
+    let mut flags: Vec<String> = Vec::new();
+    let mut paths: Vec<String> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--emit-c"                    => { emit_c = true;    }
+            _ if arg.starts_with("--")    => { flags.push(arg);  }
+            _                             => { paths.push(arg);  }
+        }
+    }
+
+    let config = match Config::parse(&flags) {
+        Ok(c)  => { c }
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    if paths.len() != 1 {
         eprintln!("You must pass exactly one filepath as an argument.");
         return;
     }
 
-    if !Path::new(&args[0]).is_file() {
-        eprintln!("File {} does not exist.", args[0]);
+    if !Path::new(&paths[0]).is_file() {
+        eprintln!("File {} does not exist.", paths[0]);
         return;
     }
 
-    let prog = match read_to_string(&args[0]) {
+    let prog = match read_to_string(&paths[0]) {
         Ok(x) => { x }
         _     => {
-            eprintln!("Error occurred reading from file {}.", args[0]);
+            eprintln!("Error occurred reading from file {}.", paths[0]);
             eprintln!("This user might not have permission to read the file.");
             eprintln!("The file might not be encoded as valid utf8.");
             return;
         }
     };
 
-    let mut count: i128 = 0;
+    // Collect every bracket with its byte offset, then match them with a stack
+    // so a mismatch can be pinned to the exact offending character. A stray `]`
+    // is whichever `]` underflows the stack; an unclosed `[` is whatever is left
+    // on the stack once the source is exhausted.
+    let mut brackets: Vec<(usize, char)> = Vec::new();
 
-    for c in prog.chars() {
+    for (off, c) in prog.char_indices() {
+        if c == '[' || c == ']' {
+            brackets.push((off, c));
+        }
+    }
+
+    let mut stack: Vec<usize> = Vec::new();
+
+    for &(off, c) in &brackets {
         match c {
-            '[' => { count += 1; }
+            '[' => { stack.push(off); }
             ']' => {
-                count -= 1;
-                if count < 0 {
-                    eprintln!("Mismatched brackets in source code.");
+                if stack.pop().is_none() {
+                    report_bracket_error(&prog, off, "unmatched `]`");
                     return;
                 }
             }
-            _ => {}
+             _  => {}
         }
     }
 
-    if count != 0 {
-        eprintln!("Mismatched brackets in source code.");
+    if let Some(off) = stack.pop() {
+        report_bracket_error(&prog, off, "unclosed `[`");
+        return;
+    }
+
+    // With `--emit-c` the tool transpiles to a standalone C program instead of
+    // interpreting it, writing `<input>.c` next to the source. The result can
+    // be compiled with any C compiler to run orders of magnitude faster than
+    // the interpreter's `run` loop.
+    if emit_c {
+        let out = format!("{}.c", paths[0]);
+        let src = Program::from_source(&prog, config).compile_to_c();
+
+        match std::fs::write(&out, src) {
+            Ok(()) => { eprintln!("Wrote {}.", out);                  }
+            _      => { eprintln!("Error occurred writing {}.", out); }
+        }
+
         return;
     }
 
-    Program::from_source(&prog).exec();
+    Program::from_source(&prog, config).run(
+        &mut stdin().bytes(),
+        &mut stdout().lock(),
+    );
 }
 
 fn get_args() -> Vec<String> {
@@ -57,25 +107,174 @@ fn get_args() -> Vec<String> {
     args.collect()
 }
 
+/// Map a byte offset into `source` to its 1-based line and column along with
+/// the text of the line it falls on, so a diagnostic can render a caret under
+/// the offending character.
+fn locate(source: &str, offset: usize) -> (usize, usize, &str) {
+    let line_start = source[..offset]
+        .rfind('\n')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    let line_end = source[line_start..]
+        .find('\n')
+        .map(|i| line_start + i)
+        .unwrap_or(source.len());
+
+    let line = source[..offset].matches('\n').count() + 1;
+    let col = source[line_start..offset].chars().count() + 1;
+
+    (line, col, &source[line_start..line_end])
+}
+
+/// Render a mismatched-bracket diagnostic pointing at the exact bracket: the
+/// line and column, the offending source line, and a caret beneath it. The
+/// blank gutters reserve the same width as the `"{:>3} | "` code-line prefix
+/// so the `|` separators and the caret line up with the source text.
+fn render_bracket_error(source: &str, offset: usize, msg: &str) -> String {
+    let (line, col, text) = locate(source, offset);
+
+    format!(
+        "error: {}\n  --> line {}:{}\n    |\n{:>3} | {}\n    | {}^\n",
+        msg,
+        line,
+        col,
+        line,
+        text,
+        " ".repeat(col - 1),
+    )
+}
+
+/// Print a mismatched-bracket diagnostic to stderr.
+fn report_bracket_error(source: &str, offset: usize, msg: &str) {
+    eprint!("{}", render_bracket_error(source, offset, msg));
+}
+
+/// What `gchr` writes into the current cell when stdin is exhausted.
+#[derive(Clone, Copy)]
+enum Eof {
+    Zero,      // write 0          (--eof=zero, the default)
+    NegOne,    // write all-ones   (--eof=neg-one)
+    Unchanged, // leave cell as-is (--eof=unchanged)
+}
+
+/// How the data pointer is bounded. `Wrapping` keeps the historical unbounded
+/// `HashMap` tape; `Bounded` errors on any move outside `0..size`.
+#[derive(Clone, Copy)]
+enum Tape {
+    Wrapping,
+    Bounded(isize),
+}
+
+/// Runtime configuration parsed from the command line, threaded through
+/// `Program` so interpreter semantics no longer depend on compile-time flags.
+#[derive(Clone, Copy)]
+struct Config {
+    eof: Eof,
+    mask: u32,
+    tape: Tape,
+    trace: bool,
+    dump_final_tape: bool,
+}
+
+impl Config {
+    /// Parse the non-positional `--flag=value` arguments into a `Config`,
+    /// returning a human-readable message on the first malformed flag.
+    fn parse(flags: &[String]) -> Result<Self, String> {
+        let mut eof = Eof::Zero;
+        let mut mask: u32 = 0xFF;
+        let mut tape = Tape::Wrapping;
+        let mut trace = false;
+        let mut dump_final_tape = false;
+
+        for flag in flags {
+            let (key, value) = match flag.split_once('=') {
+                Some((k, v)) => { (k, Some(v)) }
+                None         => { (flag.as_str(), None) }
+            };
+
+            match key {
+                "--eof" => {
+                    eof = match value {
+                        Some("zero")      => { Eof::Zero }
+                        Some("neg-one")   => { Eof::NegOne }
+                        Some("unchanged") => { Eof::Unchanged }
+                        _ => {
+                            return Err(format!(
+                                "--eof expects one of zero|neg-one|unchanged, got {:?}.",
+                                value.unwrap_or(""),
+                            ));
+                        }
+                    };
+                }
+                "--cell-size" => {
+                    mask = match value {
+                        Some("8")  => { 0xFF }
+                        Some("16") => { 0xFFFF }
+                        Some("32") => { 0xFFFF_FFFF }
+                        _ => {
+                            return Err(format!(
+                                "--cell-size expects one of 8|16|32, got {:?}.",
+                                value.unwrap_or(""),
+                            ));
+                        }
+                    };
+                }
+                "--tape" => {
+                    tape = match value {
+                        Some("wrapping") => { Tape::Wrapping }
+                        Some(v) if v.starts_with("bounded:") => {
+                            match v["bounded:".len()..].parse::<isize>() {
+                                Ok(n) if n > 0 => { Tape::Bounded(n) }
+                                _ => {
+                                    return Err(format!(
+                                        "--tape=bounded:N expects a positive integer, got {:?}.",
+                                        v,
+                                    ));
+                                }
+                            }
+                        }
+                        _ => {
+                            return Err(format!(
+                                "--tape expects wrapping|bounded:N, got {:?}.",
+                                value.unwrap_or(""),
+                            ));
+                        }
+                    };
+                }
+                "--trace"           => { trace = true;           }
+                "--dump-final-tape" => { dump_final_tape = true; }
+                _ => {
+                    return Err(format!("Unknown flag {}.", key));
+                }
+            }
+        }
+
+        Ok(Config { eof, mask, tape, trace, dump_final_tape })
+    }
+}
+
 struct Program {
     dp: isize,
     ip: usize,
     len: usize,
-    dtape: HashMap<isize, u8>,
-    itape: Vec<Token>,
-    input: Bytes<Stdin>,
+    dtape: HashMap<isize, u32>,
+    itape: Vec<Op>,
+    jump: Vec<usize>,
+    config: Config,
 }
 
 impl Program {
-    fn from_source(source: &str) -> Self {
+    fn from_source(source: &str, config: Config) -> Self {
         use Token::*;
 
         let     dp: isize;
         let     ip: usize;
         let     len: usize;
-        let mut dtape: HashMap<isize, u8>;
-        let mut itape: Vec<Token>;
-        let     input: Bytes<Stdin>;
+        let mut dtape: HashMap<isize, u32>;
+        let mut tokens: Vec<Token>;
+        let     itape: Vec<Op>;
+        let     jump: Vec<usize>;
 
         dp = 0;
         ip = 0;
@@ -83,36 +282,64 @@ impl Program {
         dtape = HashMap::new();
         dtape.insert(0, 0);
 
-        itape = Vec::new();
+        tokens = Vec::new();
         for c in source.chars() {
             match c {
-                '>' => { itape.push(INCP); }
-                '<' => { itape.push(DECP); }
-                '+' => { itape.push(INCC); }
-                '-' => { itape.push(DECC); }
-                '.' => { itape.push(PCHR); }
-                ',' => { itape.push(GCHR); }
-                '[' => { itape.push(LBRK); }
-                ']' => { itape.push(RBRK); }
-                 _  => {                   }
+                '>' => { tokens.push(INCP); }
+                '<' => { tokens.push(DECP); }
+                '+' => { tokens.push(INCC); }
+                '-' => { tokens.push(DECC); }
+                '.' => { tokens.push(PCHR); }
+                ',' => { tokens.push(GCHR); }
+                '[' => { tokens.push(LBRK); }
+                ']' => { tokens.push(RBRK); }
+                 _  => {                    }
             }
         }
 
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "itape:\n{:#?}",
-            &itape,
-        );
+        // Lower the raw token stream to the optimized `Op` IR that `run`
+        // actually executes: consecutive `+`/`-` and `>`/`<` are run-length
+        // folded, `[-]`/`[+]` collapse to `SetC`, and simple multiply/copy
+        // loops collapse to a burst of `MulAdd` plus a `SetC`.
+        itape = lower(&tokens);
+
+        if config.trace {
+            eprintln!(
+                "itape:\n{:#?}",
+                &itape,
+            );
+        }
 
         len = itape.len();
 
-        input = stdin().bytes();
+        // Precompute a bracket jump table so `lbrk`/`rbrk` never rescan the
+        // itape at runtime. `jump[ip]` maps each `LBrk`/`RBrk` index to the
+        // index of its matching bracket; non-bracket slots are never read.
+        // `main` already rejects unbalanced sources, so the stack is balanced,
+        // but the pass still asserts the invariant.
+        jump = {
+            let mut jump = vec![0; len];
+            let mut stack: Vec<usize> = Vec::new();
 
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "input:\n{:#?}",
-            &input,
-        );
+            for (i, op) in itape.iter().enumerate() {
+                match op {
+                    Op::LBrk => { stack.push(i); }
+                    Op::RBrk => {
+                        let open = stack
+                            .pop()
+                            .expect("unbalanced brackets in itape");
+
+                        jump[open] = i;
+                        jump[i] = open;
+                    }
+                    _ => {}
+                }
+            }
+
+            assert!(stack.is_empty(), "unbalanced brackets in itape");
+
+            jump
+        };
 
         Program {
             dp,
@@ -120,41 +347,60 @@ impl Program {
             len,
             dtape,
             itape,
-            input,
+            jump,
+            config,
         }
     }
 
-    fn exec(&mut self) {
-        use Token::*;
-
+    /// Run the optimized program to completion, reading cells from `input` and
+    /// writing printed bytes to `out`. The caller supplies the I/O so `main`
+    /// can wire up stdin/stdout while tests drive in-memory buffers.
+    fn run<R: Read, W: Write>(&mut self, input: &mut Bytes<R>, out: &mut W) {
         while !self.is_halted() {
             match self.itape[self.ip] {
-                INCP => { self.incp(); }
-                DECP => { self.decp(); }
-                INCC => { self.incc(); }
-                DECC => { self.decc(); }
-                PCHR => { self.pchr(); }
-                GCHR => { self.gchr(); }
-                LBRK => { self.lbrk(); }
-                RBRK => { self.rbrk(); }
+                Op::MovP(n)                 => { self.movp(n); }
+                Op::AddC(n)                 => { self.addc(n); }
+                Op::SetC(n)                 => { self.setc(n); }
+                Op::MulAdd { offset, factor } => { self.muladd(offset, factor); }
+                Op::PChr                    => { self.pchr(out); }
+                Op::GChr                    => { self.gchr(input); }
+                Op::LBrk                    => { self.lbrk(); }
+                Op::RBrk                    => { self.rbrk(); }
             }
         }
+
+        if self.config.dump_final_tape {
+            self.dump_final_tape();
+        }
     }
 
     fn is_halted(&self) -> bool {
         self.ip == self.len
     }
 
-    fn incp(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called INCP | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+    fn movp(&mut self, n: isize) {
+        if self.config.trace {
+            eprintln!(
+                "Called MOVP | IP: {:5} | DP: {:5} ({:3}) | N: {}",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+                n,
+            );
+        }
 
-        self.dp += 1;
+        self.dp += n;
+
+        if let Tape::Bounded(size) = self.config.tape {
+            if self.dp < 0 || self.dp >= size {
+                eprintln!(
+                    "Data pointer moved out of bounds: {} (tape size {}).",
+                    self.dp,
+                    size,
+                );
+                std::process::exit(1);
+            }
+        }
 
         if !self.dtape.contains_key(&self.dp) {
             self.dtape.insert(self.dp, 0);
@@ -163,162 +409,308 @@ impl Program {
         self.ip += 1;
     }
 
-    fn decp(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called DECP | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
+    fn addc(&mut self, n: i32) {
+        if self.config.trace {
+            eprintln!(
+                "Called ADDC | IP: {:5} | DP: {:5} ({:3}) | N: {}",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+                n,
+            );
+        }
+
+        self.dtape.insert(
             self.dp,
-            self.dtape[&self.dp],
+            self.dtape[&self.dp]
+                .wrapping_add(n as u32)
+                & self.config.mask,
         );
 
-        self.dp -= 1;
-
-        if !self.dtape.contains_key(&self.dp) {
-            self.dtape.insert(self.dp, 0);
-        }
-
         self.ip += 1;
     }
 
-    fn incc(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called INCC | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+    fn setc(&mut self, n: u8) {
+        if self.config.trace {
+            eprintln!(
+                "Called SETC | IP: {:5} | DP: {:5} ({:3}) | N: {}",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+                n,
+            );
+        }
 
-        self.dtape.insert(
-            self.dp,
-            self.dtape[&self.dp].wrapping_add(1),
-        );
+        self.dtape.insert(self.dp, n as u32 & self.config.mask);
 
         self.ip += 1;
     }
 
-    fn decc(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called DECC | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+    fn muladd(&mut self, offset: isize, factor: i32) {
+        if self.config.trace {
+            eprintln!(
+                "Called MULA | IP: {:5} | DP: {:5} ({:3}) | OFF: {} | FAC: {}",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+                offset,
+                factor,
+            );
+        }
+
+        let cur = self.dtape[&self.dp];
+        let target = self.dp + offset;
+        let old = *self.dtape.get(&target).unwrap_or(&0);
 
         self.dtape.insert(
-            self.dp,
-            self.dtape[&self.dp].wrapping_sub(1),
+            target,
+            old.wrapping_add(cur.wrapping_mul(factor as u32))
+                & self.config.mask,
         );
 
         self.ip += 1;
     }
 
-    fn pchr(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called PCHR | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+    fn pchr<W: Write>(&mut self, out: &mut W) {
+        if self.config.trace {
+            eprintln!(
+                "Called PCHR | IP: {:5} | DP: {:5} ({:3})",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+            );
+        }
 
-        print!(
+        write!(
+            out,
             "{}",
-            self.dtape[&self.dp] as char
-        );
-        stdout().flush().unwrap();
+            self.dtape[&self.dp] as u8 as char
+        ).unwrap();
+        out.flush().unwrap();
 
-        #[cfg(debug_assertions)]
-        eprintln!();
+        if self.config.trace {
+            eprintln!();
+        }
 
         self.ip += 1;
     }
 
-    fn gchr(&mut self) {
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called GCHR | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+    fn gchr<R: Read>(&mut self, input: &mut Bytes<R>) {
+        if self.config.trace {
+            eprintln!(
+                "Called GCHR | IP: {:5} | DP: {:5} ({:3})",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+            );
+        }
 
-        let chr = self.input.next().unwrap_or(Ok(0)).unwrap();
+        match input.next() {
+            Some(byte) => {
+                let chr = byte.unwrap();
 
-        #[cfg(debug_assertions)]
-        eprintln!("Called GHCR | Got: {:3} ({})", chr, chr as char);
+                if self.config.trace {
+                    eprintln!("Called GHCR | Got: {:3} ({})", chr, chr as char);
+                }
 
-        self.dtape.insert(self.dp, chr);
+                self.dtape.insert(self.dp, chr as u32 & self.config.mask);
+            }
+            None => {
+                // Stdin is exhausted; what lands in the cell is configurable.
+                match self.config.eof {
+                    Eof::Zero      => { self.dtape.insert(self.dp, 0);                }
+                    Eof::NegOne    => { self.dtape.insert(self.dp, self.config.mask); }
+                    Eof::Unchanged => {                                              }
+                }
+            }
+        }
 
         self.ip += 1;
     }
 
     fn lbrk(&mut self) {
-        use Token::*;
+        if self.config.trace {
+            eprintln!(
+                "Called LBRK | IP: {:5} | DP: {:5} ({:3})",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+            );
+        }
 
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called LBRK | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+        if self.dtape[&self.dp] == 0 {
+            self.ip = self.jump[self.ip] + 1;
+        } else {
+            self.ip += 1;
+        }
+    }
 
-        self.ip += 1;
+    fn rbrk(&mut self) {
+        if self.config.trace {
+            eprintln!(
+                "Called RBRK | IP: {:5} | DP: {:5} ({:3})",
+                self.ip,
+                self.dp,
+                self.dtape[&self.dp],
+            );
+        }
 
         if self.dtape[&self.dp] != 0 {
-            return;
+            self.ip = self.jump[self.ip] + 1;
+        } else {
+            self.ip += 1;
         }
+    }
 
-        let mut count = 1;
+    /// Print every non-zero cell of the final tape in ascending index order,
+    /// for inspecting a program's memory after it halts (`--dump-final-tape`).
+    fn dump_final_tape(&self) {
+        let mut cells: Vec<(&isize, &u32)> = self.dtape
+            .iter()
+            .filter(|(_, v)| **v != 0)
+            .collect();
 
-        while count != 0 {
-            match self.itape[self.ip] {
-                LBRK => { count += 1; }
-                RBRK => { count -= 1; }
-                _    => {             }
-            }
+        cells.sort();
 
-            self.ip += 1;
+        eprintln!("final tape:");
+        for (index, value) in cells {
+            eprintln!("  {:5}: {:3}", index, value);
         }
     }
 
-    fn rbrk(&mut self) {
-        use Token::*;
+    /// Transpile the optimized `Op` stream to a standalone C program.
+    ///
+    /// The emitted source honors the same `Config` the interpreter runs under,
+    /// so `--emit-c` is not a silently different program: `--cell-size` picks
+    /// the `cell` type (whose natural wraparound matches the masked `u32`
+    /// arithmetic), `--tape=bounded:N` sizes the tape and guards pointer moves,
+    /// and `--eof` controls what a `,` read writes at end of input. Printing
+    /// goes through `emit`, which reproduces `pchr`'s behavior of UTF-8
+    /// encoding the low byte (so bytes above 127 emit two bytes, matching the
+    /// interpreter rather than a raw `putchar`).
+    fn compile_to_c(&self) -> String {
+        let mut out = String::new();
+        let mut depth: usize = 1;
 
-        #[cfg(debug_assertions)]
-        eprintln!(
-            "Called RBRK | IP: {:5} | DP: {:5} ({:3})",
-            self.ip,
-            self.dp,
-            self.dtape[&self.dp],
-        );
+        // `--cell-size` selects a C type whose modular arithmetic matches the
+        // `& mask` the interpreter applies after every cell write.
+        let cell = match self.config.mask {
+            0xFFFF      => "unsigned short",
+            0xFFFF_FFFF => "unsigned int",
+            _           => "unsigned char",
+        };
 
-        if self.dtape[&self.dp] == 0 {
-            self.ip += 1;
-            return;
-        }
+        out.push_str("#include <stdio.h>\n\n");
+        out.push_str(&format!("typedef {} cell;\n\n", cell));
 
-        self.ip -= 1;
+        // Reproduce `pchr`: print the low byte as a Unicode scalar, which is
+        // UTF-8 encoded (one byte below 0x80, two bytes otherwise).
+        out.push_str("static void emit(unsigned c) {\n");
+        out.push_str("    c &= 0xFF;\n");
+        out.push_str("    if (c < 0x80) {\n");
+        out.push_str("        putchar(c);\n");
+        out.push_str("    } else {\n");
+        out.push_str("        putchar(0xC0 | (c >> 6));\n");
+        out.push_str("        putchar(0x80 | (c & 0x3F));\n");
+        out.push_str("    }\n");
+        out.push_str("}\n\n");
 
-        let mut count = 1;
+        // `--tape=bounded:N` sizes the tape to N cells with the pointer parked
+        // at index 0 (matching `dp = 0`); wrapping keeps a large centered tape.
+        match self.config.tape {
+            Tape::Bounded(size) => {
+                out.push_str(&format!("static cell tape[{}];\n\n", size));
+            }
+            Tape::Wrapping => {
+                out.push_str("static cell tape[1 << 20];\n\n");
+            }
+        }
 
-        while count != 0 {
-            match self.itape[self.ip] {
-                RBRK => { count += 1; }
-                LBRK => { count -= 1; }
-                _    => {             }
+        out.push_str("int main(void) {\n");
+        match self.config.tape {
+            Tape::Bounded(_) => {
+                out.push_str("    cell *p = tape;\n");
             }
+            Tape::Wrapping => {
+                out.push_str("    cell *p = tape + ((sizeof tape / sizeof *tape) / 2);\n");
+            }
+        }
+
+        let line = |depth: usize, text: &str, out: &mut String| {
+            for _ in 0..depth {
+                out.push_str("    ");
+            }
+
+            out.push_str(text);
+            out.push('\n');
+        };
+
+        // What a `,` read stores once stdin is exhausted, mirroring `--eof`.
+        let eof_store = match self.config.eof {
+            Eof::Zero      => Some("*p = 0;"),
+            Eof::NegOne    => Some("*p = (cell)-1;"),
+            Eof::Unchanged => None,
+        };
+
+        for op in &self.itape {
+            match op {
+                Op::MovP(n) => {
+                    line(depth, &format!("p += {};", n), &mut out);
+
+                    if let Tape::Bounded(size) = self.config.tape {
+                        line(
+                            depth,
+                            &format!(
+                                "if (p < tape || p >= tape + {}) {{ fputs(\"Data pointer moved out of bounds.\\n\", stderr); return 1; }}",
+                                size,
+                            ),
+                            &mut out,
+                        );
+                    }
+                }
+                Op::AddC(n)   => { line(depth, &format!("*p += {};", n), &mut out); }
+                Op::SetC(n)   => { line(depth, &format!("*p = {};", n), &mut out);  }
+                Op::MulAdd { offset, factor } => {
+                    line(
+                        depth,
+                        &format!("*(p + {}) += *p * {};", offset, factor),
+                        &mut out,
+                    );
+                }
+                Op::PChr => { line(depth, "emit(*p);", &mut out);                   }
+                Op::GChr => {
+                    let eof = match eof_store {
+                        Some(store) => store,
+                        None        => "/* unchanged */ ;",
+                    };
 
-            self.ip -= 1;
+                    line(
+                        depth,
+                        &format!("{{ int c = getchar(); if (c != EOF) *p = c; else {} }}", eof),
+                        &mut out,
+                    );
+                }
+                Op::LBrk => {
+                    line(depth, "while (*p) {", &mut out);
+                    depth += 1;
+                }
+                Op::RBrk => {
+                    depth -= 1;
+                    line(depth, "}", &mut out);
+                }
+            }
         }
 
-        self.ip += 2;
+        out.push_str("    return 0;\n");
+        out.push_str("}\n");
+
+        out
     }
 }
 
+/// A raw source token, one per meaningful Brainfuck character. These are only
+/// an intermediate form: `lower` folds a run of them into the optimized `Op`
+/// stream that the interpreter actually executes.
 #[derive(Debug)]
 enum Token {
     INCP, // >
@@ -330,3 +722,351 @@ enum Token {
     LBRK, // [
     RBRK, // ]
 }
+
+/// An optimized instruction. Runs of cell/pointer ops are coalesced into a
+/// single `AddC`/`MovP`, the clear idiom becomes `SetC`, and simple
+/// multiply/copy loops become a burst of `MulAdd` followed by `SetC(0)`.
+/// `LBrk`/`RBrk` carry the loops that survive optimization.
+#[derive(Debug)]
+enum Op {
+    MovP(isize),                            // net run of > / <
+    AddC(i32),                              // net run of + / -
+    SetC(u8),                               // [-] / [+]
+    MulAdd { offset: isize, factor: i32 },  // dtape[dp + offset] += dtape[dp] * factor
+    PChr,                                  // .
+    GChr,                                  // ,
+    LBrk,                                  // [
+    RBrk,                                  // ]
+}
+
+/// Lower a token stream to the optimized `Op` IR.
+fn lower(tokens: &[Token]) -> Vec<Op> {
+    use Token::*;
+
+    let mut ops = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match tokens[i] {
+            INCC | DECC => {
+                let mut delta: i32 = 0;
+
+                while i < tokens.len() {
+                    match tokens[i] {
+                        INCC => { delta = delta.wrapping_add(1); }
+                        DECC => { delta = delta.wrapping_sub(1); }
+                        _    => { break;                        }
+                    }
+
+                    i += 1;
+                }
+
+                if delta != 0 {
+                    ops.push(Op::AddC(delta));
+                }
+            }
+            INCP | DECP => {
+                let mut delta: isize = 0;
+
+                while i < tokens.len() {
+                    match tokens[i] {
+                        INCP => { delta += 1; }
+                        DECP => { delta -= 1; }
+                        _    => { break;      }
+                    }
+
+                    i += 1;
+                }
+
+                if delta != 0 {
+                    ops.push(Op::MovP(delta));
+                }
+            }
+            PCHR => { ops.push(Op::PChr); i += 1; }
+            GCHR => { ops.push(Op::GChr); i += 1; }
+            LBRK => {
+                let close = matching(tokens, i);
+
+                match fuse_loop(&tokens[i + 1..close]) {
+                    Some(fused) => {
+                        ops.extend(fused);
+                        i = close + 1;
+                    }
+                    None => {
+                        ops.push(Op::LBrk);
+                        i += 1;
+                    }
+                }
+            }
+            RBRK => { ops.push(Op::RBrk); i += 1; }
+        }
+    }
+
+    ops
+}
+
+/// Find the index of the `RBRK` that matches the `LBRK` at `open`.
+fn matching(tokens: &[Token], open: usize) -> usize {
+    use Token::*;
+
+    let mut count = 1;
+    let mut i = open + 1;
+
+    while count != 0 {
+        match tokens[i] {
+            LBRK => { count += 1; }
+            RBRK => { count -= 1; }
+            _    => {             }
+        }
+
+        i += 1;
+    }
+
+    i - 1
+}
+
+/// Try to recognize a loop body as an idiom that can run without branching.
+///
+/// Returns `Some` for the clear idiom (`[-]` / `[+]`) and for simple
+/// multiply/copy loops: bodies that only move the pointer and add to cells,
+/// return the pointer to its origin, and decrement the current cell by exactly
+/// one per iteration. Anything else (I/O, nested loops, unbalanced pointer,
+/// non-unit counter decrement) returns `None` and is kept as a real loop.
+fn fuse_loop(body: &[Token]) -> Option<Vec<Op>> {
+    use Token::*;
+
+    if body.len() == 1 && matches!(body[0], INCC | DECC) {
+        return Some(vec![Op::SetC(0)]);
+    }
+
+    let mut offset: isize = 0;
+    let mut deltas: HashMap<isize, i32> = HashMap::new();
+
+    for tok in body {
+        match tok {
+            INCP => { offset += 1; }
+            DECP => { offset -= 1; }
+            INCC => {
+                let e = deltas.entry(offset).or_insert(0);
+                *e = e.wrapping_add(1);
+            }
+            DECC => {
+                let e = deltas.entry(offset).or_insert(0);
+                *e = e.wrapping_sub(1);
+            }
+            _ => { return None; }
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+
+    if deltas.get(&0).copied().unwrap_or(0) != -1 {
+        return None;
+    }
+
+    let mut offsets: Vec<isize> = deltas
+        .keys()
+        .copied()
+        .filter(|o| *o != 0 && deltas[o] != 0)
+        .collect();
+
+    offsets.sort();
+
+    let mut ops: Vec<Op> = offsets
+        .into_iter()
+        .map(|o| Op::MulAdd { offset: o, factor: deltas[&o] })
+        .collect();
+
+    ops.push(Op::SetC(0));
+
+    Some(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn config(mask: u32) -> Config {
+        Config {
+            eof: Eof::Zero,
+            mask,
+            tape: Tape::Wrapping,
+            trace: false,
+            dump_final_tape: false,
+        }
+    }
+
+    /// A deliberately naive, character-at-a-time reference interpreter to check
+    /// the optimized `Op` stream against. It mirrors the same cell semantics
+    /// (wrapping `u32` masked to the configured cell width, zero on EOF) but
+    /// does no run-length folding or loop fusion, so any divergence points at
+    /// the optimizer rather than at shared behavior.
+    fn naive(source: &str, input: &[u8], mask: u32) -> (Vec<u8>, HashMap<isize, u32>) {
+        let chars: Vec<char> = source
+            .chars()
+            .filter(|c| "><+-.,[]".contains(*c))
+            .collect();
+
+        let mut dtape: HashMap<isize, u32> = HashMap::new();
+        let mut dp: isize = 0;
+        let mut ip: usize = 0;
+        let mut input = input.iter();
+        let mut out: Vec<u8> = Vec::new();
+
+        while ip < chars.len() {
+            match chars[ip] {
+                '>' => { dp += 1; }
+                '<' => { dp -= 1; }
+                '+' => {
+                    let c = dtape.entry(dp).or_insert(0);
+                    *c = c.wrapping_add(1) & mask;
+                }
+                '-' => {
+                    let c = dtape.entry(dp).or_insert(0);
+                    *c = c.wrapping_sub(1) & mask;
+                }
+                '.' => {
+                    let v = *dtape.get(&dp).unwrap_or(&0);
+                    let mut buf = [0u8; 4];
+                    let s = (v as u8 as char).encode_utf8(&mut buf);
+                    out.extend_from_slice(s.as_bytes());
+                }
+                ',' => {
+                    match input.next() {
+                        Some(&b) => { dtape.insert(dp, b as u32 & mask); }
+                        None     => { dtape.insert(dp, 0);               }
+                    }
+                }
+                '[' => {
+                    if *dtape.get(&dp).unwrap_or(&0) == 0 {
+                        let mut depth = 1;
+                        while depth != 0 {
+                            ip += 1;
+                            match chars[ip] {
+                                '[' => { depth += 1; }
+                                ']' => { depth -= 1; }
+                                _   => {             }
+                            }
+                        }
+                    }
+                }
+                ']' => {
+                    if *dtape.get(&dp).unwrap_or(&0) != 0 {
+                        let mut depth = 1;
+                        while depth != 0 {
+                            ip -= 1;
+                            match chars[ip] {
+                                ']' => { depth += 1; }
+                                '[' => { depth -= 1; }
+                                _   => {             }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            ip += 1;
+        }
+
+        (out, dtape)
+    }
+
+    /// Run the real optimized interpreter into in-memory buffers.
+    fn optimized(source: &str, input: &[u8], mask: u32) -> (Vec<u8>, HashMap<isize, u32>) {
+        let mut prog = Program::from_source(source, config(mask));
+        let mut out: Vec<u8> = Vec::new();
+        let mut bytes = Cursor::new(input.to_vec()).bytes();
+
+        prog.run(&mut bytes, &mut out);
+
+        (out, prog.dtape)
+    }
+
+    /// Assert the optimized interpreter agrees with the naive one on both the
+    /// printed bytes and the final non-zero tape cells.
+    fn assert_matches(source: &str, input: &[u8], mask: u32) {
+        let (want_out, want_tape) = naive(source, input, mask);
+        let (got_out, got_tape) = optimized(source, input, mask);
+
+        assert_eq!(got_out, want_out, "output diverged from the naive interpreter");
+
+        let nonzero = |t: &HashMap<isize, u32>| {
+            let mut v: Vec<(isize, u32)> = t
+                .iter()
+                .map(|(k, x)| (*k, *x))
+                .filter(|(_, x)| *x != 0)
+                .collect();
+            v.sort();
+            v
+        };
+
+        assert_eq!(
+            nonzero(&got_tape),
+            nonzero(&want_tape),
+            "final tape diverged from the naive interpreter",
+        );
+    }
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    #[test]
+    fn hello_world_matches_naive_and_expected() {
+        assert_matches(HELLO, &[], 0xFF);
+
+        let (out, _) = optimized(HELLO, &[], 0xFF);
+        assert_eq!(out, b"Hello World!\n");
+    }
+
+    #[test]
+    fn multiply_loop_matches_naive() {
+        // `[->+++<]` is a multiply loop the optimizer fuses to a `MulAdd`; the
+        // naive reference runs it branch-by-branch.
+        assert_matches("+++++[->+++<]", &[], 0xFF);
+    }
+
+    #[test]
+    fn echo_with_input_matches_naive() {
+        assert_matches(",[.,]", b"nkdbfi\n", 0xFF);
+    }
+
+    #[test]
+    fn bracket_caret_points_at_the_bracket() {
+        // `abc[def+`: the unclosed `[` is at column 4, and the caret must land
+        // directly under it with the `|` gutters aligned to the source text.
+        let rendered = render_bracket_error("abc[def+", 3, "unclosed `[`");
+
+        assert_eq!(
+            rendered,
+            "error: unclosed `[`\n  --> line 1:4\n    |\n  1 | abc[def+\n    |    ^\n",
+        );
+
+        // The caret column in the last line must equal the bracket column in
+        // the code line (both measured from the shared 6-character prefix).
+        let lines: Vec<&str> = rendered.lines().collect();
+        let code = lines[3];
+        let caret = lines[4];
+
+        assert_eq!(
+            code.find('['),
+            caret.find('^'),
+            "caret is not aligned with the bracket",
+        );
+    }
+
+    #[test]
+    fn wide_run_is_not_truncated_to_8_bits() {
+        // 300 `+` must leave 300 in a 16-bit cell, not 300 & 0xFF == 44. A
+        // differential on the final tape catches a fold that truncates the
+        // run's net delta to 8 bits.
+        let src = "+".repeat(300);
+        assert_matches(&src, &[], 0xFFFF);
+
+        let (_, tape) = optimized(&src, &[], 0xFFFF);
+        assert_eq!(tape.get(&0).copied(), Some(300));
+    }
+}